@@ -7,20 +7,35 @@
 
 #![warn(clippy::all, missing_docs, rust_2018_idioms)]
 
+mod metrics;
 mod server;
+mod zeroconf;
 
 use std::io;
+use std::net::IpAddr;
+use std::path::PathBuf;
 use std::pin::Pin;
 use std::task::{Context, Poll};
 
 use futures_core::Stream;
 use librespot_core as core;
+use tokio::runtime::Handle;
+use tracing::{debug, info, info_span};
 
 use self::server::DiscoveryServer;
 
+pub use self::metrics::DiscoveryMetrics;
+
+#[cfg(feature = "with-dns-sd")]
+pub use self::zeroconf::DnsSdBackend;
+pub use self::zeroconf::{LibmdnsBackend, ZeroconfBackend, ZeroconfService};
+
 /// Credentials to be used in [`librespot`](`librespot_core`).
 pub use crate::core::authentication::Credentials;
 
+/// Reused to persist and reload the [`Credentials`] yielded by discovery.
+pub use crate::core::cache::Cache;
+
 /// Determining the icon in the list of available devices.
 pub use crate::core::config::DeviceType;
 
@@ -30,13 +45,25 @@ pub use crate::core::config::DeviceType;
 /// is selected in the list of available devices, it yields [`Credentials`].
 pub struct Discovery {
     server: DiscoveryServer,
-    _svc: libmdns::Service,
+    _svc: Box<dyn ZeroconfService>,
+    credentials_cache: Option<Cache>,
+    credentials_cache_path: Option<PathBuf>,
+    cached_credentials: Option<Credentials>,
+    metrics: DiscoveryMetrics,
+    port: u16,
 }
 
 /// A builder for [`Discovery`].
 pub struct Builder {
     server_config: server::Config,
     port: u16,
+    credentials_cache: Option<PathBuf>,
+    zeroconf_backend: Box<dyn ZeroconfBackend>,
+    zeroconf_interfaces: Vec<IpAddr>,
+    metrics: DiscoveryMetrics,
+    service_type: String,
+    txt_records: Vec<String>,
+    runtime_handle: Option<Handle>,
 }
 
 impl Builder {
@@ -49,9 +76,53 @@ impl Builder {
                 device_id,
             },
             port: 0,
+            credentials_cache: None,
+            zeroconf_backend: Box::new(LibmdnsBackend),
+            zeroconf_interfaces: Vec::new(),
+            metrics: DiscoveryMetrics::default(),
+            service_type: "_spotify-connect._tcp".to_owned(),
+            txt_records: vec!["VERSION=1.0".to_owned(), "CPath=/".to_owned()],
+            runtime_handle: None,
         }
     }
 
+    /// Overrides the advertised service type. Default is
+    /// `"_spotify-connect._tcp"`.
+    pub fn service_type(mut self, service_type: String) -> Self {
+        self.service_type = service_type;
+        self
+    }
+
+    /// Adds a TXT record to the advertised service.
+    ///
+    /// The `VERSION=1.0` and `CPath=/` records required by Spotify Connect are
+    /// advertised by default; this appends further entries so integrators can
+    /// advertise additional metadata.
+    pub fn txt_record(mut self, key: &str, value: &str) -> Self {
+        self.txt_records.push(format!("{key}={value}"));
+        self
+    }
+
+    /// Spawns the mDNS responder on the provided runtime instead of the
+    /// current one.
+    ///
+    /// This matters when the [`Stream`] is driven from a runtime other than
+    /// the one that was current at [`launch`](Self::launch) time.
+    pub fn runtime_handle(mut self, handle: Handle) -> Self {
+        self.runtime_handle = Some(handle);
+        self
+    }
+
+    /// Returns a handle to the [`DiscoveryMetrics`] updated while discovery
+    /// runs.
+    ///
+    /// The returned handle shares its counters with the [`Discovery`] produced
+    /// by [`launch`](Self::launch), so a host can grab it beforehand to poll
+    /// discovery health.
+    pub fn metrics(&self) -> DiscoveryMetrics {
+        self.metrics.clone()
+    }
+
     /// Sets the name to be displayed. Default is `"Librespot"`.
     pub fn name(mut self, name: String) -> Self {
         self.server_config.name = name.into();
@@ -71,6 +142,53 @@ impl Builder {
         self
     }
 
+    /// Caches the [`Credentials`] received over discovery at `path` and reuses
+    /// them on the next run.
+    ///
+    /// When set, every credential blob yielded by the discovery server is
+    /// written to disk (using `librespot_core`'s [`Cache`]), and any blob
+    /// stored from a previous run is yielded as the first [`Stream`] item.
+    /// The service is still advertised over mDNS by [`launch`](Self::launch),
+    /// so the device is already visible when the cached blob is yielded. A cached blob is
+    /// yielded only once per run, and a fresh selection in Spotify overwrites
+    /// it.
+    ///
+    /// Discovery cannot observe whether a blob is accepted upstream, so it does
+    /// not invalidate the stored file on its own. If the caller finds the
+    /// cached credentials are rejected, it should call
+    /// [`Discovery::invalidate_credentials`] so the stale blob is not yielded
+    /// again on the next run.
+    pub fn credentials_cache(mut self, path: impl Into<PathBuf>) -> Self {
+        self.credentials_cache = Some(path.into());
+        self
+    }
+
+    /// Selects the backend used to advertise the service over zeroconf.
+    ///
+    /// Defaults to [`LibmdnsBackend`], which carries its own mDNS stack. On a
+    /// host that already runs Avahi or Bonjour, pass [`DnsSdBackend`] instead
+    /// so the system daemon owns UDP 5353.
+    pub fn zeroconf_backend(mut self, backend: impl ZeroconfBackend + 'static) -> Self {
+        self.zeroconf_backend = Box::new(backend);
+        self
+    }
+
+    /// Restricts the interfaces on which the service is advertised and the
+    /// [`DiscoveryServer`] listens.
+    ///
+    /// By default the service is advertised on every interface and the HTTP
+    /// listener binds to `0.0.0.0`. Passing an allow-list of addresses — for
+    /// example only the IPv4 address of `wlan0` — keeps Spotify Connect off a
+    /// management or VPN interface on multi-homed devices and containers.
+    ///
+    /// The [`LibmdnsBackend`] advertises only on the listed addresses. The
+    /// `DnsSdBackend` ignores the list when advertising, so with it the list
+    /// restricts only the HTTP listener.
+    pub fn zeroconf_interfaces(mut self, interfaces: impl IntoIterator<Item = IpAddr>) -> Self {
+        self.zeroconf_interfaces = interfaces.into_iter().collect();
+        self
+    }
+
     /// Sets up the [`Discovery`] instance.
     ///
     /// # Errors
@@ -86,21 +204,96 @@ impl Discovery {
         Builder::new(device_id)
     }
 
+    /// Returns a handle to the [`DiscoveryMetrics`] tracking discovery
+    /// activity.
+    pub fn metrics(&self) -> DiscoveryMetrics {
+        self.metrics.clone()
+    }
+
+    /// Returns the port the [`DiscoveryServer`] is actually listening on.
+    ///
+    /// This is useful when [`Builder::port`] was left at its default of `0`,
+    /// which lets the operating system choose a free port.
+    pub fn port(&self) -> u16 {
+        self.port
+    }
+
+    /// Discards any credentials cached by [`Builder::credentials_cache`].
+    ///
+    /// Call this when the cached credentials were rejected upstream — which
+    /// discovery itself cannot observe — so the stale blob is neither yielded
+    /// again this run nor reloaded on the next one. It drops the pending blob
+    /// and removes the stored file; the next selection in Spotify repopulates
+    /// the cache. Does nothing when no cache is configured.
+    ///
+    /// # Errors
+    /// Returns an error if the stored credentials file exists but cannot be
+    /// removed.
+    pub fn invalidate_credentials(&mut self) -> io::Result<()> {
+        self.cached_credentials = None;
+
+        if let Some(path) = &self.credentials_cache_path {
+            match std::fs::remove_file(path.join("credentials.json")) {
+                Ok(()) => {}
+                Err(e) if e.kind() == io::ErrorKind::NotFound => {}
+                Err(e) => return Err(e),
+            }
+        }
+
+        Ok(())
+    }
+
     fn new(builder: Builder) -> io::Result<Self> {
+        let _span = info_span!("discovery", name = %builder.server_config.name).entered();
+
         let name = builder.server_config.name.clone();
         let mut port = builder.port;
-        let server = DiscoveryServer::new(builder.server_config, &mut port)
-            .map_err(|e| io::Error::new(io::ErrorKind::Other, e))?;
-        let responder = libmdns::Responder::spawn(&tokio::runtime::Handle::current())?;
+        let metrics = builder.metrics.clone();
+
+        let credentials_cache_path = builder.credentials_cache.clone();
+        let credentials_cache = match builder.credentials_cache {
+            Some(path) => Some(
+                Cache::new(Some(path), None::<PathBuf>, None::<PathBuf>, None)
+                    .map_err(|e| io::Error::new(io::ErrorKind::Other, e))?,
+            ),
+            None => None,
+        };
+        let cached_credentials = credentials_cache.as_ref().and_then(Cache::credentials);
+
+        let server = DiscoveryServer::new(
+            builder.server_config,
+            &mut port,
+            &builder.zeroconf_interfaces,
+            metrics.clone(),
+        )
+        .map_err(|e| io::Error::new(io::ErrorKind::Other, e))?;
 
-        let svc = responder.register(
-            "_spotify-connect._tcp".to_owned(),
+        let handle = match &builder.runtime_handle {
+            Some(handle) => handle.clone(),
+            None => Handle::current(),
+        };
+        let txt: Vec<&str> = builder.txt_records.iter().map(String::as_str).collect();
+
+        let svc = builder.zeroconf_backend.register(
+            builder.service_type,
             name.into_owned(),
             port,
-            &["VERSION=1.0", "CPath=/"],
-        );
+            &txt,
+            &builder.zeroconf_interfaces,
+            &handle,
+        )?;
+
+        info!(port, "zeroconf service registered");
 
-        Ok(Self { server, _svc: svc })
+        Ok(Self {
+            server,
+            _svc: svc,
+            credentials_cache,
+            credentials_cache_path,
+            cached_credentials,
+            metrics,
+            port,
+        })
     }
 }
 
@@ -108,6 +301,20 @@ impl Stream for Discovery {
     type Item = Credentials;
 
     fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
-        Pin::new(&mut self.server).poll_next(cx)
+        if let Some(credentials) = self.cached_credentials.take() {
+            debug!("yielding cached credentials");
+            return Poll::Ready(Some(credentials));
+        }
+
+        match Pin::new(&mut self.server).poll_next(cx) {
+            Poll::Ready(Some(credentials)) => {
+                debug!(user = %credentials.username.as_deref().unwrap_or("<unknown>"), "yielding credentials");
+                if let Some(cache) = &self.credentials_cache {
+                    cache.save_credentials(&credentials);
+                }
+                Poll::Ready(Some(credentials))
+            }
+            other => other,
+        }
     }
 }
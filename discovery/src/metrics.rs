@@ -0,0 +1,59 @@
+//! A cheap, cloneable handle onto counters tracking discovery health.
+//!
+//! [`DiscoveryMetrics`] is shared between [`Discovery`](crate::Discovery) and
+//! the [`DiscoveryServer`](crate::server::DiscoveryServer) so that a host
+//! application can surface the discovery lifecycle without parsing the
+//! `tracing` output. The counters are updated as connections open and close
+//! and as requests arrive.
+
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+
+#[derive(Debug, Default)]
+struct Inner {
+    active_connections: AtomicUsize,
+    requests_handled: AtomicUsize,
+    last_error: Mutex<Option<String>>,
+}
+
+/// A handle to the counters tracking discovery activity.
+///
+/// Cloning yields another handle onto the same counters, so a host can keep a
+/// copy to poll while discovery runs.
+#[derive(Clone, Debug, Default)]
+pub struct DiscoveryMetrics {
+    inner: Arc<Inner>,
+}
+
+impl DiscoveryMetrics {
+    pub(crate) fn connection_opened(&self) {
+        self.inner.active_connections.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub(crate) fn connection_closed(&self) {
+        self.inner.active_connections.fetch_sub(1, Ordering::Relaxed);
+    }
+
+    pub(crate) fn request_handled(&self) {
+        self.inner.requests_handled.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub(crate) fn record_error(&self, error: impl ToString) {
+        *self.inner.last_error.lock().unwrap() = Some(error.to_string());
+    }
+
+    /// The number of connections currently being served.
+    pub fn active_connections(&self) -> usize {
+        self.inner.active_connections.load(Ordering::Relaxed)
+    }
+
+    /// The total number of `addUser`/`getInfo` requests handled so far.
+    pub fn requests_handled(&self) -> usize {
+        self.inner.requests_handled.load(Ordering::Relaxed)
+    }
+
+    /// The most recent error reported by the discovery server, if any.
+    pub fn last_error(&self) -> Option<String> {
+        self.inner.last_error.lock().unwrap().clone()
+    }
+}
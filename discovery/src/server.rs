@@ -0,0 +1,325 @@
+use std::borrow::Cow;
+use std::collections::BTreeMap;
+use std::net::{IpAddr, Ipv4Addr, SocketAddr};
+use std::pin::Pin;
+use std::sync::Arc;
+use std::task::{Context, Poll};
+
+use aes_ctr::cipher::generic_array::GenericArray;
+use aes_ctr::cipher::{NewStreamCipher, SyncStreamCipher};
+use aes_ctr::Aes128Ctr;
+use futures_core::Stream;
+use hmac::{Hmac, Mac, NewMac};
+use hyper::service::{make_service_fn, service_fn};
+use hyper::{Body, Method, Request, Response, StatusCode};
+use serde_json::json;
+use sha1::{Digest, Sha1};
+use tokio::sync::{mpsc, watch};
+use tracing::{debug, info, warn};
+
+use crate::core::authentication::Credentials;
+use crate::core::config::DeviceType;
+use crate::core::diffie_hellman::DhLocalKeys;
+use crate::DiscoveryMetrics;
+
+type Params<'a> = BTreeMap<Cow<'a, str>, Cow<'a, str>>;
+
+pub struct Config {
+    pub name: Cow<'static, str>,
+    pub device_type: DeviceType,
+    pub device_id: String,
+}
+
+struct RequestHandler {
+    config: Config,
+    keys: DhLocalKeys,
+    tx: mpsc::UnboundedSender<Credentials>,
+    metrics: DiscoveryMetrics,
+}
+
+/// Keeps [`DiscoveryMetrics::active_connections`] in sync for the lifetime of a
+/// single HTTP connection.
+struct ConnectionGuard {
+    metrics: DiscoveryMetrics,
+}
+
+impl ConnectionGuard {
+    fn new(metrics: DiscoveryMetrics) -> Self {
+        metrics.connection_opened();
+        Self { metrics }
+    }
+}
+
+impl Drop for ConnectionGuard {
+    fn drop(&mut self) {
+        self.metrics.connection_closed();
+    }
+}
+
+impl RequestHandler {
+    fn new(
+        config: Config,
+        metrics: DiscoveryMetrics,
+    ) -> (Self, mpsc::UnboundedReceiver<Credentials>) {
+        let (tx, rx) = mpsc::unbounded_channel();
+        let handler = Self {
+            config,
+            keys: DhLocalKeys::random(&mut rand::thread_rng()),
+            tx,
+            metrics,
+        };
+
+        (handler, rx)
+    }
+
+    fn handle_get_info(&self) -> Response<hyper::Body> {
+        info!("handling getInfo request");
+        self.metrics.request_handled();
+
+        let public_key = base64::encode(self.keys.public_key());
+        let device_type: &str = self.config.device_type.into();
+
+        let body = json!({
+            "status": 101,
+            "statusString": "ERROR-OK",
+            "spotifyError": 0,
+            "version": "2.7.1",
+            "deviceID": (self.config.device_id),
+            "remoteName": (self.config.name),
+            "activeUser": "",
+            "publicKey": (public_key),
+            "deviceType": (device_type),
+            "libraryVersion": crate::core::version::SEMVER,
+            "accountReq": "PREMIUM",
+            "brandDisplayName": "librespot",
+            "modelDisplayName": "librespot",
+            "resolverVersion": "0",
+            "groupStatus": "NONE",
+            "voiceSupport": "NO",
+        })
+        .to_string();
+
+        Response::new(Body::from(body))
+    }
+
+    fn handle_add_user(&self, params: &Params<'_>) -> Response<hyper::Body> {
+        self.metrics.request_handled();
+
+        let (username, encrypted_blob, client_key) =
+            match (params.get("userName"), params.get("blob"), params.get("clientKey")) {
+                (Some(username), Some(blob), Some(client_key)) => {
+                    (username.as_ref(), blob, client_key)
+                }
+                _ => {
+                    warn!("addUser request is missing required parameters");
+                    self.metrics.record_error("addUser missing parameters");
+                    return self.error_response("ERROR-INVALID");
+                }
+            };
+
+        info!(user = %username, "handling addUser request");
+
+        let encrypted_blob = match base64::decode(encrypted_blob.as_bytes()) {
+            Ok(blob) => blob,
+            Err(e) => {
+                warn!("addUser request for user {:?} has an invalid blob: {}", username, e);
+                self.metrics.record_error("addUser invalid blob");
+                return self.error_response("ERROR-INVALID");
+            }
+        };
+
+        let client_key = match base64::decode(client_key.as_bytes()) {
+            Ok(key) => key,
+            Err(e) => {
+                warn!("addUser request for user {:?} has an invalid clientKey: {}", username, e);
+                self.metrics.record_error("addUser invalid clientKey");
+                return self.error_response("ERROR-INVALID");
+            }
+        };
+
+        if encrypted_blob.len() < 20 {
+            warn!("addUser request for user {:?} has a truncated blob", username);
+            self.metrics.record_error("addUser truncated blob");
+            return self.error_response("ERROR-INVALID");
+        }
+
+        let shared_key = self.keys.shared_secret(&client_key);
+        debug!("Diffie-Hellman handshake completed");
+
+        let encrypted = &encrypted_blob[0..encrypted_blob.len() - 20];
+        let cksum = &encrypted_blob[encrypted_blob.len() - 20..encrypted_blob.len()];
+
+        let base_key = Sha1::digest(&shared_key);
+        let base_key = &base_key[..16];
+
+        let checksum_key = {
+            let mut h =
+                Hmac::<Sha1>::new_from_slice(base_key).expect("HMAC can take key of any size");
+            h.update(b"checksum");
+            h.finalize().into_bytes()
+        };
+
+        let encryption_key = {
+            let mut h =
+                Hmac::<Sha1>::new_from_slice(base_key).expect("HMAC can take key of any size");
+            h.update(b"encryption");
+            h.finalize().into_bytes()
+        };
+
+        let mut h =
+            Hmac::<Sha1>::new_from_slice(&checksum_key).expect("HMAC can take key of any size");
+        h.update(encrypted);
+        if h.verify(cksum).is_err() {
+            warn!("Login error for user {:?}: MAC mismatch", username);
+            self.metrics.record_error("MAC mismatch");
+            return self.error_response("ERROR-MAC");
+        }
+
+        let decrypted = {
+            let mut data = encrypted.to_vec();
+            let mut cipher = Aes128Ctr::new(
+                GenericArray::from_slice(&encryption_key[0..16]),
+                GenericArray::from_slice(&[0u8; 16]),
+            );
+            cipher.apply_keystream(&mut data);
+            data
+        };
+
+        let credentials = Credentials::with_blob(username, &decrypted, &self.config.device_id);
+
+        info!(user = %username, "credentials received over discovery");
+        let _ = self.tx.send(credentials);
+
+        let result = json!({
+            "status": 101,
+            "spotifyError": 0,
+            "statusString": "ERROR-OK"
+        });
+
+        let body = result.to_string();
+        Response::new(Body::from(body))
+    }
+
+    fn not_found(&self) -> Response<hyper::Body> {
+        let mut res = Response::default();
+        *res.status_mut() = StatusCode::NOT_FOUND;
+        res
+    }
+
+    fn error_response(&self, status_string: &str) -> Response<hyper::Body> {
+        let result = json!({
+            "status": 102,
+            "spotifyError": 1,
+            "statusString": status_string,
+        });
+
+        Response::new(Body::from(result.to_string()))
+    }
+
+    async fn handle(self: Arc<Self>, request: Request<Body>) -> hyper::Result<Response<Body>> {
+        let mut params = Params::new();
+
+        let (parts, body) = request.into_parts();
+
+        if let Some(query) = parts.uri.query() {
+            let query_params = form_urlencoded::parse(query.as_bytes());
+            params.extend(query_params);
+        }
+
+        let body = hyper::body::to_bytes(body).await?;
+        params.extend(form_urlencoded::parse(&body));
+
+        let action = params.get("action").map(Cow::as_ref);
+
+        Ok(match (parts.method, action) {
+            (Method::GET, Some("getInfo")) => self.handle_get_info(),
+            (Method::POST, Some("addUser")) => self.handle_add_user(&params),
+            _ => self.not_found(),
+        })
+    }
+}
+
+pub struct DiscoveryServer {
+    cred_rx: mpsc::UnboundedReceiver<Credentials>,
+    _close_tx: watch::Sender<()>,
+}
+
+impl DiscoveryServer {
+    pub fn new(
+        config: Config,
+        port: &mut u16,
+        interfaces: &[IpAddr],
+        metrics: DiscoveryMetrics,
+    ) -> hyper::Result<Self> {
+        let (discovery, cred_rx) = RequestHandler::new(config, metrics.clone());
+        let discovery = Arc::new(discovery);
+
+        // One listener per advertised address so the listen set matches what
+        // the zeroconf backend advertises; they all feed the same channel. An
+        // empty allow-list keeps the previous behaviour of binding `0.0.0.0`.
+        let addresses: Vec<IpAddr> = if interfaces.is_empty() {
+            vec![Ipv4Addr::UNSPECIFIED.into()]
+        } else {
+            interfaces.to_vec()
+        };
+
+        let (close_tx, close_rx) = watch::channel(());
+
+        let mut bound_port = *port;
+        for (index, &ip) in addresses.iter().enumerate() {
+            let address = SocketAddr::new(ip, bound_port);
+            let discovery = discovery.clone();
+            let metrics = metrics.clone();
+
+            let make_service = make_service_fn(move |_| {
+                let discovery = discovery.clone();
+                // One guard per connection, shared with every request served on
+                // it so the connection counts as active until it closes.
+                let guard = Arc::new(ConnectionGuard::new(metrics.clone()));
+                async move {
+                    Ok::<_, hyper::Error>(service_fn(move |request| {
+                        let discovery = discovery.clone();
+                        let guard = guard.clone();
+                        async move {
+                            let _guard = guard;
+                            discovery.handle(request).await
+                        }
+                    }))
+                }
+            });
+
+            let server = hyper::Server::try_bind(&address)?.serve(make_service);
+
+            // Pin every listener to the port the first one was granted so that
+            // a `port` of `0` still yields a single shared port.
+            if index == 0 {
+                bound_port = server.local_addr().port();
+            }
+            info!("zeroconf HTTP server listening on {}", server.local_addr());
+
+            let mut close_rx = close_rx.clone();
+            tokio::spawn(async move {
+                let _ = server
+                    .with_graceful_shutdown(async move {
+                        let _ = close_rx.changed().await;
+                    })
+                    .await;
+            });
+        }
+
+        *port = bound_port;
+
+        Ok(Self {
+            cred_rx,
+            _close_tx: close_tx,
+        })
+    }
+}
+
+impl Stream for DiscoveryServer {
+    type Item = Credentials;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        self.cred_rx.poll_recv(cx)
+    }
+}
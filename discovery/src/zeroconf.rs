@@ -0,0 +1,114 @@
+//! Pluggable backends that advertise the discovery service over zeroconf.
+//!
+//! The bundled [`LibmdnsBackend`] carries its own mDNS stack, which is handy
+//! on systems without a responder but conflicts with a system-wide Avahi or
+//! Bonjour daemon. On such hosts [`DnsSdBackend`] delegates to the OS daemon
+//! so that a single responder owns UDP 5353.
+
+use std::io;
+use std::net::IpAddr;
+
+use tokio::runtime::Handle;
+
+/// A live zeroconf registration. Dropping it unregisters the service.
+pub trait ZeroconfService: Send {}
+
+/// Advertises the discovery service on the local network.
+///
+/// This is the extension point selected through
+/// [`Builder::zeroconf_backend`](crate::Builder::zeroconf_backend). An
+/// implementation registers a single service and hands back a
+/// [`ZeroconfService`] handle that keeps the advertisement alive until it is
+/// dropped.
+pub trait ZeroconfBackend {
+    /// Registers `name` under `service_type` on `port` with the given TXT
+    /// records and returns a handle that unregisters the service on drop.
+    ///
+    /// When `interfaces` is non-empty, a backend that filters by address
+    /// advertises the service only on those addresses; an empty slice
+    /// advertises on every interface. [`DnsSdBackend`] does not filter and
+    /// leaves interface selection to the system daemon. Backends that
+    /// spawn their own task use `handle` as the runtime to spawn it on.
+    fn register(
+        &self,
+        service_type: String,
+        name: String,
+        port: u16,
+        txt: &[&str],
+        interfaces: &[IpAddr],
+        handle: &Handle,
+    ) -> io::Result<Box<dyn ZeroconfService>>;
+}
+
+/// The bundled [`libmdns`] responder.
+///
+/// This is the default backend. It ships its own mDNS stack and therefore
+/// does not depend on a daemon being present, at the cost of conflicting with
+/// one that is.
+pub struct LibmdnsBackend;
+
+struct LibmdnsService {
+    _responder: libmdns::Responder,
+    _svc: libmdns::Service,
+}
+
+impl ZeroconfService for LibmdnsService {}
+
+impl ZeroconfBackend for LibmdnsBackend {
+    fn register(
+        &self,
+        service_type: String,
+        name: String,
+        port: u16,
+        txt: &[&str],
+        interfaces: &[IpAddr],
+        handle: &Handle,
+    ) -> io::Result<Box<dyn ZeroconfService>> {
+        let responder = if interfaces.is_empty() {
+            libmdns::Responder::spawn(handle)?
+        } else {
+            libmdns::Responder::spawn_with_ip_list(handle, interfaces.to_vec())?
+        };
+        let svc = responder.register(service_type, name, port, txt);
+
+        Ok(Box::new(LibmdnsService {
+            _responder: responder,
+            _svc: svc,
+        }))
+    }
+}
+
+/// A native DNS-SD backend that delegates to the system daemon.
+///
+/// Use this on hosts that already run Avahi or Bonjour to avoid
+/// double-advertising and port conflicts on UDP 5353.
+#[cfg(feature = "with-dns-sd")]
+pub struct DnsSdBackend;
+
+#[cfg(feature = "with-dns-sd")]
+struct DnsSdService {
+    _svc: dns_sd::DNSService,
+}
+
+#[cfg(feature = "with-dns-sd")]
+impl ZeroconfService for DnsSdService {}
+
+#[cfg(feature = "with-dns-sd")]
+impl ZeroconfBackend for DnsSdBackend {
+    fn register(
+        &self,
+        service_type: String,
+        name: String,
+        port: u16,
+        txt: &[&str],
+        // The system daemon owns interface selection and its own task, so the
+        // allow-list and runtime handle only apply to the bundled responder.
+        _interfaces: &[IpAddr],
+        _handle: &Handle,
+    ) -> io::Result<Box<dyn ZeroconfService>> {
+        let svc = dns_sd::DNSService::register(Some(&name), &service_type, None, None, port, txt)
+            .map_err(|e| io::Error::new(io::ErrorKind::Other, e))?;
+
+        Ok(Box::new(DnsSdService { _svc: svc }))
+    }
+}